@@ -0,0 +1,114 @@
+// Scanner benchmarks over generated programs of a few megabytes:
+//
+// - scanning a &str, which scans bytes, against collecting the program into
+//   a Vec<char> first;
+// - scan_tokens, which sizes the token vector up front, against collecting
+//   the token iterator into a vector that starts empty;
+// - identifier-heavy programs whose names repeat against ones whose names
+//   are all distinct, to show what interning saves.
+//
+//     cargo run --release --example scan_bench
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use rlox::scanner::{Scanner, Token};
+
+const SIZE: usize = 8 << 20;
+const RUNS: u32 = 5;
+
+// Counts allocations, reallocations included, and the bytes they ask for.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let mixed = program(|i| format!("(name_{} + {}.5) * \"some text\" >= -[1, 2][0] // note\n", i % 97, i));
+    let megabytes = mixed.len() as f64 / 1e6;
+    println!("mixed program, {:.1} MB", megabytes);
+    report("Vec<char>", megabytes, measure(|| {
+        let mut scanner = Scanner::new(mixed.chars().collect());
+        scanner.scan_tokens();
+    }));
+    report("bytes", megabytes, measure(|| {
+        let mut scanner = Scanner::from_source(&mixed);
+        scanner.scan_tokens();
+    }));
+    report("bytes, unsized Vec", megabytes, measure(|| {
+        let tokens: Vec<Token> = Scanner::from_source(&mixed).map(Result::unwrap).collect();
+        drop(tokens);
+    }));
+
+    // Both spell every name with six digits, so they scan the same tokens.
+    let repeated = program(|i| format!("alpha_{0:06} + beta_{0:06} * gamma_{0:06} - delta_{0:06}\n", i % 10));
+    let distinct = program(|i| format!("alpha_{0:06} + beta_{0:06} * gamma_{0:06} - delta_{0:06}\n", i));
+    println!("identifiers, {:.1} MB", repeated.len() as f64 / 1e6);
+    for (name, source) in [("40 distinct names", &repeated), ("all names distinct", &distinct)] {
+        report(name, source.len() as f64 / 1e6, measure(|| {
+            let mut scanner = Scanner::from_source(source);
+            scanner.scan_tokens();
+        }));
+    }
+}
+
+// Repeats `line`, numbered from 0, until the program is SIZE bytes long.
+fn program(line: impl Fn(usize) -> String) -> String {
+    let mut source = String::with_capacity(SIZE + 128);
+    let mut i = 0;
+    while source.len() < SIZE {
+        source.push_str(&line(i));
+        i += 1;
+    }
+    source
+}
+
+struct Measurement {
+    time: Duration,
+    allocations: usize,
+    bytes: usize,
+}
+
+// The best time of RUNS runs. Every run allocates the same, so the counts
+// come from the last one.
+fn measure(mut scan: impl FnMut()) -> Measurement {
+    let mut best = Measurement { time: Duration::MAX, allocations: 0, bytes: 0 };
+    for _ in 0..RUNS {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let bytes = BYTES.load(Ordering::Relaxed);
+        let start = Instant::now();
+        scan();
+        best.time = best.time.min(start.elapsed());
+        best.allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        best.bytes = BYTES.load(Ordering::Relaxed) - bytes;
+    }
+    best
+}
+
+fn report(name: &str, megabytes: f64, measurement: Measurement) {
+    println!("  {:<20} {:>8.2?} {:>6.1} MB/s {:>9} allocations {:>7.1} MB allocated", name,
+             measurement.time, megabytes / measurement.time.as_secs_f64(),
+             measurement.allocations, measurement.bytes as f64 / 1e6);
+}
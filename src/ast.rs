@@ -1,180 +1,339 @@
+use std::borrow::Cow;
+
+use crate::config::Config;
 use crate::scanner;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     Binary(Box<Expr>, scanner::Token, Box<Expr>),
     Grouping(Box<Expr>),
     Literal(scanner::Token),
     Unary(scanner::Token, Box<Expr>),
+    Interpolation(Vec<Expr>),
+    Array(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+}
+
+pub trait Visitor<T> {
+    fn visit_binary(&mut self, left: &Expr, operator: &scanner::Token, right: &Expr) -> T;
+    fn visit_grouping(&mut self, expr: &Expr) -> T;
+    fn visit_literal(&mut self, value: &scanner::Token) -> T;
+    fn visit_unary(&mut self, operator: &scanner::Token, right: &Expr) -> T;
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> T;
+    fn visit_array(&mut self, elements: &[Expr]) -> T;
+    fn visit_index(&mut self, target: &Expr, index: &Expr) -> T;
+}
+
+impl Expr {
+    pub fn accept<T>(&self, visitor: &mut dyn Visitor<T>) -> T {
+        match self {
+            Expr::Binary(left, operator, right) => visitor.visit_binary(left, operator, right),
+            Expr::Grouping(expr) => visitor.visit_grouping(expr),
+            Expr::Literal(value) => visitor.visit_literal(value),
+            Expr::Unary(operator, right) => visitor.visit_unary(operator, right),
+            Expr::Interpolation(parts) => visitor.visit_interpolation(parts),
+            Expr::Array(elements) => visitor.visit_array(elements),
+            Expr::Index(target, index) => visitor.visit_index(target, index),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_to_json(expr: &Expr) -> String {
+    serde_json::to_string(expr).expect("Failed to serialize AST")
 }
 
 /*
+ * parse          → expression EOF ;
  * expression     → equality ;
- * equality       → comparison ( ( "!=" | "==" ) comparison )* ;
- * comparison     → addition ( ( ">" | ">=" | "<" | "<=" ) addition )* ;
+ * equality       → bit_or ( ( "!=" | "==" ) bit_or )* ;
+ * bit_or         → bit_xor ( "|" bit_xor )* ;
+ * bit_xor        → bit_and ( "^" bit_and )* ;
+ * bit_and        → comparison ( "&" comparison )* ;
+ * comparison     → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
+ * shift          → addition ( ( "<<" | ">>" ) addition )* ;
  * addition       → multiplication ( ( "-" | "+" ) multiplication )* ;
  * multiplication → unary ( ( "/" | "*" ) unary )* ;
- * unary          → ( "!" | "-" ) unary
- *                | primary ;
- * primary        → NUMBER | STRING | "false" | "true" | "nil"
- *                | "(" expression ")" ;
+ * unary          → ( "!" | "-" | "~" ) unary
+ *                | power ;
+ * power          → index ( "**" unary )? ;
+ * index          → primary ( "[" expression "]" )* ;
+ * primary        → NUMBER | STRING | CHAR | "false" | "true" | "nil"
+ *                | "(" expression ")" | interpolation | array ;
+ * array          → "[" ( expression ( "," expression )* )? "]" ;
+ * interpolation  → ( INTERPOLATION expression )+ STRING ;
  */
 
-pub struct Parser {
+#[derive(Debug)]
+pub struct ParseError {
+    pub token: scanner::Token,
+    pub message: &'static str,
+}
+
+impl ParseError {
+    fn new(token: scanner::Token, message: &'static str) -> ParseError {
+        ParseError { token, message }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.token.token_type == scanner::TokenType::EOF
+    }
+}
+
+type Rule<'a> = fn(&mut Parser<'a>) -> Result<Expr, ParseError>;
+
+pub struct Parser<'a> {
     current: usize,
-    tokens: Vec<scanner::Token>,
+    tokens: Cow<'a, [scanner::Token]>,
+    depth: usize,
+    max_depth: usize,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<scanner::Token>) -> Parser {
+// Parsers borrow their tokens when given a slice, so the scanner's output
+// isn't copied, and own them when given a Vec.
+impl<'a> Parser<'a> {
+    pub fn new(tokens: impl Into<Cow<'a, [scanner::Token]>>) -> Parser<'a> {
+        Parser::with_config(tokens, &Config::default())
+    }
+
+    pub fn with_config(tokens: impl Into<Cow<'a, [scanner::Token]>>, config: &Config) -> Parser<'a> {
+        let mut tokens = tokens.into();
+        // peek() relies on the stream always ending in EOF. Only a stream
+        // without one is copied to add it.
+        if tokens.last().map(|token| &token.token_type) != Some(&scanner::TokenType::EOF) {
+            let line = tokens.last().map_or(1, |token| token.line);
+            let end = tokens.last().map_or(0, |token| token.span.1);
+            tokens.to_mut().push(scanner::Token {
+                token_type: scanner::TokenType::EOF,
+                lexeme: String::new(),
+                line,
+                span: (end, end),
+            });
+        }
+
         Parser {
             current: 0,
-            tokens
+            tokens,
+            depth: 0,
+            max_depth: config.max_depth,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Expr, &'static str> {
-        self.expression()
+    pub fn parse(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.expression()?;
+        // check() never matches EOF, so this can't go through consume().
+        if !self.is_at_end() {
+            return Err(ParseError::new(self.peek().clone(), "Expect end of expression."));
+        }
+
+        Ok(expr)
     }
 
-    fn expression(&mut self) -> Result<Expr, &'static str> {
-        self.equality()
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.nested(Parser::equality)
     }
 
-    fn equality(&mut self) -> Result<Expr, &'static str> {
-        let mut expr = self.comparison();
-        while self.match_token(vec![scanner::TokenType::BangEqual,
-                                    scanner::TokenType::EqualEqual]) {
-            let operator = self.previous();
-            let right = self.comparison();
-            if right.is_ok() && expr.is_ok() {
-                expr = Ok(Expr::Binary(Box::new(expr.unwrap()), operator, Box::new(right.unwrap())));
-            } else {
-                return Err("expecting equality");
-            }
+    // Rules that recurse into themselves go through here, so deeply nested
+    // input is an error rather than a native stack overflow.
+    fn nested(&mut self, rule: Rule<'a>) -> Result<Expr, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::new(self.peek().clone(), "Too deeply nested."));
         }
 
+        self.depth += 1;
+        let expr = rule(self);
+        self.depth -= 1;
         expr
     }
 
-    fn comparison(&mut self) -> Result<Expr, &'static str> {
-        let mut expr = self.addition();
-        while self.match_token(vec![scanner::TokenType::Greater,
-                                    scanner::TokenType::GreaterEqual,
-                                    scanner::TokenType::Less,
-                                    scanner::TokenType::LessEqual]) {
-            let operator = self.previous();
-            let right = self.addition();
-            if right.is_ok() && expr.is_ok() {
-                expr = Ok(Expr::Binary(Box::new(expr.unwrap()), operator, Box::new(right.unwrap())));
-            } else {
-                return Err("expecting comparison");
-            }
+    // A left-associative chain: operand ( operator operand )*. Each operator
+    // puts the tree a level deeper without the parser recursing, so it counts
+    // toward the limit too; printing or dropping the tree recurses per level.
+    fn binary(&mut self, operators: &[scanner::TokenType], operand: Rule<'a>) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut expr = operand(self)?;
+        while self.match_token(operators) {
+            let operator = self.previous().clone();
+            self.deepen()?;
+            let right = operand(self)?;
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
 
-        expr
+        self.depth = depth;
+        Ok(expr)
     }
 
-    fn addition(&mut self) -> Result<Expr, &'static str> {
-        let mut expr = self.multiplication();
-        while self.match_token(vec![scanner::TokenType::Plus,
-                                    scanner::TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.multiplication();
-            if right.is_ok() && expr.is_ok() {
-                expr = Ok(Expr::Binary(Box::new(expr.unwrap()), operator, Box::new(right.unwrap())));
-            } else {
-                return Err("expecting addition");
-            }
+    fn deepen(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::new(self.previous().clone(), "Too deeply nested."));
         }
 
-        expr
+        self.depth += 1;
+        Ok(())
     }
 
-    fn multiplication(&mut self) -> Result<Expr, &'static str> {
-        let mut expr = self.unary();
-        while self.match_token(vec![scanner::TokenType::Star,
-                                    scanner::TokenType::Slash]) {
-            let operator = self.previous();
-            let right = self.unary();
-            if right.is_ok() && expr.is_ok() {
-                expr = Ok(Expr::Binary(Box::new(expr.unwrap()), operator, Box::new(right.unwrap())));
-            } else {
-                return Err("expecting multiplication");
-            }
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::BangEqual,
+                      scanner::TokenType::EqualEqual], Parser::bit_or)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Pipe], Parser::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Caret], Parser::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Amp], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Greater,
+                      scanner::TokenType::GreaterEqual,
+                      scanner::TokenType::Less,
+                      scanner::TokenType::LessEqual], Parser::shift)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::LessLess,
+                      scanner::TokenType::GreaterGreater], Parser::addition)
+    }
+
+    fn addition(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Plus,
+                      scanner::TokenType::Minus], Parser::multiplication)
+    }
+
+    fn multiplication(&mut self) -> Result<Expr, ParseError> {
+        self.binary(&[scanner::TokenType::Star,
+                      scanner::TokenType::Slash], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.match_token(&[scanner::TokenType::Bang,
+                              scanner::TokenType::Minus,
+                              scanner::TokenType::Tilde]) {
+            let operator = self.previous().clone();
+            let right = self.nested(Parser::unary)?;
+            return Ok(Expr::Unary(operator, Box::new(right)));
         }
 
-        expr
+        self.power()
     }
 
-    fn unary(&mut self) -> Result<Expr, &'static str> {
-        if self.match_token(vec![scanner::TokenType::Bang,
-                                 scanner::TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.unary();
-            if right.is_ok() {
-                return Ok(Expr::Unary(operator, Box::new(right.unwrap())));
-            } else {
-                return Err("expecting unary");
-            }
+    // Right-associative, and binds tighter than a prefix operator on its
+    // left: -2 ** 2 is -(2 ** 2) and 2 ** 3 ** 2 is 2 ** (3 ** 2).
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.index()?;
+        if self.match_token(&[scanner::TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.nested(Parser::unary)?;
+            return Ok(Expr::Binary(Box::new(expr), operator, Box::new(right)));
         }
 
-        let primary = self.primary();
-        if primary.is_ok() {
-            return Ok(primary.unwrap());
-        } else {
-            return Err("expecting primary");
+        Ok(expr)
+    }
+
+    // Subscripts chain like binary operators, a[1][2] being (a[1])[2].
+    fn index(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        while self.match_token(&[scanner::TokenType::LeftBracket]) {
+            let index = self.expression()?;
+            self.consume(scanner::TokenType::RightBracket, "Expect ']' after index.")?;
+            expr = Expr::Index(Box::new(expr), Box::new(index));
+            self.deepen()?;
         }
+
+        self.depth = depth;
+        Ok(expr)
     }
 
-    fn primary(&mut self) -> Result<Expr, &'static str> {
-        if self.match_token(vec![scanner::TokenType::False]) {
-            return Ok(Expr::Literal(self.previous()));
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if self.match_token(&[scanner::TokenType::False]) {
+            return Ok(Expr::Literal(self.previous().clone()));
         }
-        if self.match_token(vec![scanner::TokenType::True]) {
-            return Ok(Expr::Literal(self.previous()));
+        if self.match_token(&[scanner::TokenType::True]) {
+            return Ok(Expr::Literal(self.previous().clone()));
         }
-        if self.match_token(vec![scanner::TokenType::Nil]) {
-            return Ok(Expr::Literal(self.previous()));
+        if self.match_token(&[scanner::TokenType::Nil]) {
+            return Ok(Expr::Literal(self.previous().clone()));
         }
 
         if let scanner::TokenType::Number(_) = self.peek().token_type {
             self.advance();
-            return Ok(Expr::Literal(self.previous()));
+            return Ok(Expr::Literal(self.previous().clone()));
         }
 
         if let scanner::TokenType::String(_) = self.peek().token_type {
             self.advance();
-            return Ok(Expr::Literal(self.previous()));
+            return Ok(Expr::Literal(self.previous().clone()));
+        }
+
+        if let scanner::TokenType::Char(_) = self.peek().token_type {
+            self.advance();
+            return Ok(Expr::Literal(self.previous().clone()));
         }
 
-        if self.match_token(vec![scanner::TokenType::LeftParen]) {
-            let expr = self.expression();
+        if let scanner::TokenType::Interpolation(_) = self.peek().token_type {
+            return self.interpolation();
+        }
 
-            let consumed = self.consume(scanner::TokenType::RightParen, "Expect ')' after expression");
-            if consumed.is_err() {
-                return Err("Expect ')' after expression");
-            }
+        if self.match_token(&[scanner::TokenType::LeftParen]) {
+            let expr = self.expression()?;
+            self.consume(scanner::TokenType::RightParen, "Expect ')' after expression.")?;
+            return Ok(Expr::Grouping(Box::new(expr)));
+        }
+
+        if self.match_token(&[scanner::TokenType::LeftBracket]) {
+            return self.array();
+        }
+
+        Err(ParseError::new(self.peek().clone(), "Expect expression."))
+    }
+
+    fn interpolation(&mut self) -> Result<Expr, ParseError> {
+        let mut parts = Vec::new();
+        while let scanner::TokenType::Interpolation(_) = self.peek().token_type {
+            parts.push(Expr::Literal(self.advance().clone()));
+            parts.push(self.expression()?);
+        }
 
-            if expr.is_ok() {
-                return Ok(Expr::Grouping(Box::new(expr.unwrap())));
-            } else {
-                return Err("expecting grouping");
+        if let scanner::TokenType::String(_) = self.peek().token_type {
+            parts.push(Expr::Literal(self.advance().clone()));
+            return Ok(Expr::Interpolation(parts));
+        }
+
+        Err(ParseError::new(self.peek().clone(), "Expect '}' after interpolated expression."))
+    }
+
+    fn array(&mut self) -> Result<Expr, ParseError> {
+        let mut elements = Vec::new();
+        if !self.check(&scanner::TokenType::RightBracket) {
+            loop {
+                elements.push(self.expression()?);
+                if !self.match_token(&[scanner::TokenType::Comma]) {
+                    break;
+                }
             }
         }
 
-        Err("expecting expression")
+        self.consume(scanner::TokenType::RightBracket, "Expect ']' after array elements.")?;
+        Ok(Expr::Array(elements))
     }
 
-    fn consume(&mut self, token_type: scanner::TokenType, message: &'static str) -> Result<scanner::Token, &'static str> {
-        if self.check(token_type) {
-            return Ok(self.advance());
+    fn consume(&mut self, token_type: scanner::TokenType, message: &'static str) -> Result<scanner::Token, ParseError> {
+        if self.check(&token_type) {
+            return Ok(self.advance().clone());
         }
 
-        Err(message)
+        Err(ParseError::new(self.peek().clone(), message))
     }
 
-    fn match_token(&mut self, tokens: Vec<scanner::TokenType>) -> bool {
+    fn match_token(&mut self, tokens: &[scanner::TokenType]) -> bool {
         for token in tokens {
             if self.check(token) {
                 self.advance();
@@ -182,34 +341,260 @@ impl Parser {
             }
         }
 
-        return false;
+        false
     }
 
-    fn check(&mut self, token_type: scanner::TokenType) -> bool {
+    fn check(&self, token_type: &scanner::TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
-        let token = self.peek();
-        token.token_type == token_type
+        &self.peek().token_type == token_type
     }
 
-    fn advance(&mut self) -> scanner::Token {
+    fn advance(&mut self) -> &scanner::Token {
         if !self.is_at_end() {
             self.current += 1;
         }
-        return self.previous();
+        self.previous()
     }
 
     fn is_at_end(&self) -> bool {
-        let token = self.peek();
-        token.token_type == scanner::TokenType::EOF
+        self.peek().token_type == scanner::TokenType::EOF
+    }
+
+    fn peek(&self) -> &scanner::Token {
+        &self.tokens[self.current]
+    }
+
+    /// Looks `n` tokens past the current one without consuming anything.
+    /// Looking beyond the end saturates at the EOF token, so this is never
+    /// `None` in practice; the `Option` leaves room for a lazily scanned
+    /// token stream.
+    ///
+    /// ```
+    /// use rlox::ast::Parser;
+    /// use rlox::scanner::{tokenize, TokenType};
+    ///
+    /// let parser = Parser::new(tokenize("1 + 2").unwrap());
+    /// assert_eq!(parser.peek_n(1).unwrap().token_type, TokenType::Plus);
+    /// assert_eq!(parser.peek_n(3).unwrap().token_type, TokenType::EOF);
+    /// assert_eq!(parser.peek_n(100).unwrap().token_type, TokenType::EOF);
+    /// ```
+    pub fn peek_n(&self, n: usize) -> Option<&scanner::Token> {
+        let last = self.tokens.len() - 1;
+        self.tokens.get(self.current.saturating_add(n).min(last))
+    }
+
+    fn previous(&self) -> &scanner::Token {
+        &self.tokens[self.current - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use crate::scanner::{tokenize, TokenType};
+
+    #[test]
+    fn empty_token_stream_does_not_panic() {
+        let error = Parser::new(vec![]).parse().unwrap_err();
+        assert!(error.is_at_end());
+        assert_eq!(error.message, "Expect expression.");
+    }
+
+    #[test]
+    fn missing_eof_does_not_panic() {
+        let mut tokens = tokenize("1 +").unwrap();
+        tokens.pop();
+        let error = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(error.token.token_type, TokenType::EOF);
+        assert_eq!(error.token.line, 1);
+        assert_eq!(error.token.span, (3, 3));
+    }
+
+    // Counts literal nodes, descending through every other kind of node.
+    struct LiteralCounter;
+
+    impl Visitor<usize> for LiteralCounter {
+        fn visit_binary(&mut self, left: &Expr, _operator: &scanner::Token, right: &Expr) -> usize {
+            left.accept(self) + right.accept(self)
+        }
+
+        fn visit_grouping(&mut self, expr: &Expr) -> usize {
+            expr.accept(self)
+        }
+
+        fn visit_literal(&mut self, _value: &scanner::Token) -> usize {
+            1
+        }
+
+        fn visit_unary(&mut self, _operator: &scanner::Token, right: &Expr) -> usize {
+            right.accept(self)
+        }
+
+        fn visit_interpolation(&mut self, parts: &[Expr]) -> usize {
+            parts.iter().map(|part| part.accept(self)).sum()
+        }
+
+        fn visit_array(&mut self, elements: &[Expr]) -> usize {
+            elements.iter().map(|element| element.accept(self)).sum()
+        }
+
+        fn visit_index(&mut self, target: &Expr, index: &Expr) -> usize {
+            target.accept(self) + index.accept(self)
+        }
+    }
+
+    fn count_literals(source: &str) -> usize {
+        let expr = Parser::new(tokenize(source).unwrap()).parse().unwrap();
+        expr.accept(&mut LiteralCounter)
+    }
+
+    #[test]
+    fn visitor_counts_literals() {
+        assert_eq!(count_literals("1"), 1);
+        assert_eq!(count_literals("-(1 + 2) * 3"), 3);
+        assert_eq!(count_literals("[true, nil][0]"), 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip_is_equal() {
+        let source = "-(1.5 + 2) ** \"a ${'b'} c\" >= [nil, true][0]";
+        let expr = Parser::new(tokenize(source).unwrap()).parse().unwrap();
+        let json = ast_to_json(&expr);
+        let back: Expr = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, expr);
+    }
+
+    #[test]
+    fn interpolation_parses_its_parts() {
+        let expr = Parser::new(tokenize("\"x is ${1 + 1}\"").unwrap()).parse().unwrap();
+        let parts = match expr {
+            Expr::Interpolation(parts) => parts,
+            other => panic!("expected an interpolation, got {:?}", other),
+        };
+        assert_eq!(parts.len(), 3);
+        assert!(matches!(&parts[0], Expr::Literal(token) if token.token_type == TokenType::Interpolation(String::from("x is "))));
+        assert!(matches!(&parts[1], Expr::Binary(_, operator, _) if operator.token_type == TokenType::Plus));
+        assert!(matches!(&parts[2], Expr::Literal(token) if token.token_type == TokenType::String(String::new())));
+    }
+
+    fn print(source: &str) -> String {
+        let expr = Parser::new(tokenize(source).unwrap()).parse().unwrap();
+        crate::printer::print_ast(&expr)
+    }
+
+    #[test]
+    fn bitwise_binds_tighter_than_equality() {
+        assert_eq!(print("6 & 3 == 2"), "(== (& 6 3) 2)");
+        assert_eq!(print("5 | 2 == 7"), "(== (| 5 2) 7)");
+        assert_eq!(print("~0 == -1"), "(== (~ 0) (- 1))");
+        assert_eq!(print("1 | 2 ^ 3 & 4"), "(| 1 (^ 2 (& 3 4)))");
+    }
+
+    #[test]
+    fn shifts_sit_between_comparison_and_addition() {
+        assert_eq!(print("1 << 4 == 16"), "(== (<< 1 4) 16)");
+        assert_eq!(print("256 >> 2 == 64"), "(== (>> 256 2) 64)");
+        assert_eq!(print("1 << 2 + 3 < 4"), "(< (<< 1 (+ 2 3)) 4)");
+        assert_eq!(print("1 <= 2 >= 3"), "(>= (<= 1 2) 3)");
+    }
+
+    #[test]
+    fn trailing_tokens_are_an_error() {
+        for (source, lexeme) in [("1 2", "2"), ("1 )", ")"), ("1 += 2", "+="), ("(1) (2)", "(")] {
+            let error = Parser::new(tokenize(source).unwrap()).parse().unwrap_err();
+            assert_eq!(error.message, "Expect end of expression.", "{}", source);
+            assert_eq!(error.token.lexeme, lexeme, "{}", source);
+        }
+    }
+
+    #[test]
+    fn peek_n_saturates_at_eof() {
+        let parser = Parser::new(tokenize("1 + 2").unwrap());
+        assert_eq!(parser.peek_n(0).unwrap().token_type, TokenType::Number(1.0));
+        assert_eq!(parser.peek_n(2).unwrap().token_type, TokenType::Number(2.0));
+        assert_eq!(parser.peek_n(3).unwrap().token_type, TokenType::EOF);
+        assert_eq!(parser.peek_n(usize::MAX).unwrap().token_type, TokenType::EOF);
+
+        let parser = Parser::new(vec![]);
+        assert_eq!(parser.peek_n(5).unwrap().token_type, TokenType::EOF);
+    }
+
+    // Spawned threads get 2 MB of stack unless asked for more; the default
+    // limit has to stop every kind of nesting well within that, for the
+    // parser and for everything that walks or drops the tree.
+    #[test]
+    fn default_depth_limit_fits_a_spawned_thread() {
+        let within = Config::default().max_depth - 1;
+        let shapes: [fn(usize) -> String; 6] = [
+            |n| format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            |n| format!("{}1{}", "[".repeat(n), "]".repeat(n)),
+            |n| format!("1{}1{}", "[1".repeat(n), "]".repeat(n)),
+            |n| format!("{}1{}", "\"${".repeat(n), "}\"".repeat(n)),
+            |n| format!("1{}", " + 1".repeat(n)),
+            |n| format!("1{}", "[0]".repeat(n)),
+        ];
+
+        std::thread::Builder::new().stack_size(2 << 20).spawn(move || {
+            for shape in shapes {
+                let parse = |source: String| Parser::new(tokenize(&source).unwrap()).parse();
+                let expr = parse(shape(within)).unwrap();
+                crate::printer::print_ast(&expr);
+                crate::printer::rpn(&expr);
+                crate::printer::to_dot(&expr);
+                drop(expr);
+                assert_eq!(parse(shape(within + 1)).unwrap_err().message, "Too deeply nested.");
+                assert_eq!(parse(shape(200_000)).unwrap_err().message, "Too deeply nested.");
+            }
+        }).unwrap().join().unwrap();
+    }
+
+    // Counts heap allocations made on this thread while `allocations` runs.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|n| n + 1)));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        ALLOCATIONS.with(|count| count.set(Some(0)));
+        let value = f();
+        (value, ALLOCATIONS.with(|count| count.replace(None)).unwrap())
     }
 
-    fn peek(&self) -> scanner::Token {
-        self.tokens[self.current].clone()
+    #[test]
+    fn parsing_allocates_only_the_tree() {
+        let tokens = tokenize(&vec!["1"; 50].join(" + ")).unwrap();
+        let (_, parsing) = allocations(|| Parser::new(&tokens).parse().unwrap());
+        // The tree holds a copy of each of the 50 literals and 49 operators,
+        // and each Binary boxes its two operands. A parser copying the token
+        // stream would allocate a lexeme per token on top.
+        assert_eq!(parsing, 50 + 49 * 3);
     }
 
-    fn previous(&self) -> scanner::Token {
-        self.tokens[self.current - 1].clone()
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(print("2 ** 3 ** 2"), "(** 2 (** 3 2))");
+        assert_eq!(print("-2 ** 2"), "(- (** 2 2))");
+        assert_eq!(print("2 ** -1"), "(** 2 (- 1))");
+        assert_eq!(print("2 * 3 ** 2"), "(* 2 (** 3 2))");
     }
 }
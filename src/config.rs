@@ -0,0 +1,46 @@
+/// Options shared by the scanner and the parser. Config::default() is
+/// standard Lox.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Scans `.5` as the number 0.5 instead of Dot then 5. Off by default,
+    /// matching Lox.
+    ///
+    /// ```
+    /// use rlox::config::Config;
+    /// use rlox::scanner::{Scanner, TokenType};
+    ///
+    /// let mut scanner = Scanner::new(".5".chars().collect());
+    /// scanner.scan_tokens();
+    /// assert_eq!(scanner.tokens[0].token_type, TokenType::Dot);
+    ///
+    /// let config = Config { allow_leading_dot_numbers: true, ..Config::default() };
+    /// let mut scanner = Scanner::with_config(".5".chars().collect(), &config);
+    /// scanner.scan_tokens();
+    /// assert_eq!(scanner.tokens[0].token_type, TokenType::Number(0.5));
+    /// ```
+    pub allow_leading_dot_numbers: bool,
+    /// How deeply expressions may nest before parsing fails with "Too deeply
+    /// nested." rather than overflowing the stack. Defaults to 64.
+    ///
+    /// ```
+    /// use rlox::ast::Parser;
+    /// use rlox::scanner::tokenize;
+    ///
+    /// let source = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+    /// let mut parser = Parser::new(tokenize(&source).unwrap());
+    /// assert_eq!(parser.parse().unwrap_err().message, "Too deeply nested.");
+    /// ```
+    pub max_depth: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            allow_leading_dot_numbers: false,
+            // A debug build spends about 20 KB of stack per level of
+            // parentheses, so 64 levels fit in the 2 MB stack of a spawned
+            // thread, the test harness's included.
+            max_depth: 64,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::ast;
+use crate::scanner;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: u32,
+    pub span: (usize, usize),
+    pub location: String,
+    pub message: &'static str,
+}
+
+impl Diagnostic {
+    pub fn new(line: u32, span: (usize, usize), location: String, message: &'static str) -> Diagnostic {
+        Diagnostic { line, span, location, message }
+    }
+
+    /// The header and snippet exactly as report prints them. With `color`,
+    /// "Error" is red, the offending lexeme bold and the carets cyan.
+    ///
+    /// ```
+    /// let diagnostic = rlox::parse("1 + )").unwrap_err().remove(0);
+    ///
+    /// let plain = diagnostic.format("1 + )", false);
+    /// assert_eq!(plain, "[line 1] Error at ')'\n   1 | 1 + )\n     |     ^ Expect expression.");
+    ///
+    /// let colored = diagnostic.format("1 + )", true);
+    /// assert!(colored.starts_with("\x1b[31m[line 1] Error\x1b[0m at '\x1b[1m)\x1b[0m'"));
+    /// assert!(colored.contains("\x1b[36m^\x1b[0m"));
+    /// ```
+    pub fn format(&self, source: &str, color: bool) -> String {
+        // The header takes its number from the snippet's line, so the two
+        // agree for errors at the very end of the input.
+        let (_, line) = self.position(strip_bom(source));
+        if !color {
+            return format!("[line {}] Error{}\n{}", line, self.location, self.render(source));
+        }
+
+        // Only the lexeme in " at 'x'" is bold.
+        let location = match self.location.strip_prefix(" at '").and_then(|rest| rest.strip_suffix('\'')) {
+            Some(lexeme) => format!(" at '\x1b[1m{}\x1b[0m'", lexeme),
+            None => self.location.clone(),
+        };
+        format!("\x1b[31m[line {}] Error\x1b[0m{}\n{}", line, location, self.snippet(source, true))
+    }
+
+    /// The source line holding the span, numbered, with carets under the
+    /// span followed by the message. Tabs before the span are kept in the
+    /// padding so the carets line up however the terminal expands them.
+    ///
+    /// ```
+    /// let source = "1 +\n2 * )";
+    /// let diagnostic = rlox::parse(source).unwrap_err().remove(0);
+    /// assert_eq!(diagnostic.render(source), "   2 | 2 * )\n     |     ^ Expect expression.");
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.snippet(source, false)
+    }
+
+    // The offset the snippet points at and the number of its line.
+    fn position(&self, source: &str) -> (usize, usize) {
+        let mut start = self.span.0.min(source.len());
+        // Point an error at the very end of the input at the last line's end
+        // rather than at the empty line after a trailing newline.
+        if start == source.len() {
+            let trimmed = source.strip_suffix('\n').unwrap_or(source);
+            start = trimmed.strip_suffix('\r').unwrap_or(trimmed).len();
+        }
+
+        // Line breaks are counted the way the scanner counts them: "\r\n",
+        // "\n" and a bare "\r" are one each.
+        let before = &source[..line_start(source, start)];
+        let number = before.matches(['\n', '\r']).count() - before.matches("\r\n").count() + 1;
+        (start, number)
+    }
+
+    fn snippet(&self, source: &str, color: bool) -> String {
+        let source = strip_bom(source);
+        let (start, number) = self.position(source);
+        let line_start = line_start(source, start);
+        let text = source[line_start..].split(['\n', '\r']).next().unwrap_or("");
+        let column = (start - line_start).min(text.len());
+
+        // Keep tabs in the padding so the caret lines up with the source.
+        let padding: String = text[..column].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = self.span.1.min(line_start + text.len()).max(start);
+        let width = source[start..end].chars().count().max(1);
+
+        let number = number.to_string();
+        let gutter = " ".repeat(number.len().max(4));
+        let mut carets = "^".repeat(width);
+        if color {
+            carets = format!("\x1b[36m{}\x1b[0m", carets);
+        }
+        format!("{:>4} | {}\n{} | {}{} {}", number, text, gutter, padding, carets, self.message)
+    }
+}
+
+// Spans are relative to the source after the scanner drops a BOM.
+fn strip_bom(source: &str) -> &str {
+    source.strip_prefix('\u{FEFF}').unwrap_or(source)
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind(['\n', '\r']).map_or(0, |i| i + 1)
+}
+
+impl From<&scanner::ScanError> for Diagnostic {
+    fn from(error: &scanner::ScanError) -> Diagnostic {
+        Diagnostic::new(error.line, error.span, String::new(), error.message)
+    }
+}
+
+impl From<&ast::ParseError> for Diagnostic {
+    fn from(error: &ast::ParseError) -> Diagnostic {
+        let token = &error.token;
+        let location = if error.is_at_end() {
+            String::from(" at end")
+        } else {
+            format!(" at '{}'", token.lexeme)
+        };
+        Diagnostic::new(token.line, token.span, location, error.message)
+    }
+}
+
+pub fn report(source: &str, diagnostic: &Diagnostic, color: bool) {
+    eprintln!("{}", diagnostic.format(source, color));
+}
+
+// Whether report should colour its output by default. Follows
+// https://no-color.org: a NO_COLOR that is set and not empty disables
+// escapes.
+pub fn use_color() -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && io::stderr().is_terminal()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_puts_the_caret_under_the_span() {
+        // A tab and a two-byte character come before the ')' on its line.
+        let source = "1 +\n\t\"é\" + )";
+        let diagnostic = crate::parse(source).unwrap_err().remove(0);
+        assert_eq!(diagnostic.span, (12, 13));
+        assert_eq!(diagnostic.render(source), "   2 | \t\"é\" + )\n     | \t      ^ Expect expression.");
+
+        let diagnostic = Diagnostic::new(1, (4, 7), String::new(), "Bad.");
+        assert_eq!(diagnostic.render("1 + abc - 2"), "   1 | 1 + abc - 2\n     |     ^^^ Bad.");
+    }
+
+    #[test]
+    fn error_at_end_names_the_snippet_line() {
+        for source in ["1 +\n", "1 +\r\n", "1 +"] {
+            let diagnostic = crate::parse(source).unwrap_err().remove(0);
+            let text = diagnostic.format(source, false);
+            assert!(text.starts_with("[line 1] Error at end\n   1 | 1 +\n"), "{:?}", text);
+        }
+
+        let source = "1 +\n\n";
+        let diagnostic = crate::parse(source).unwrap_err().remove(0);
+        assert!(diagnostic.format(source, false).starts_with("[line 2] Error at end\n   2 | \n"));
+    }
+}
@@ -0,0 +1,177 @@
+pub mod config;
+pub mod scanner;
+pub mod ast;
+pub mod printer;
+pub mod diagnostics;
+
+use config::Config;
+use diagnostics::Diagnostic;
+
+pub struct CompileResult {
+    pub tokens: Vec<scanner::Token>,
+    pub ast: Option<ast::Expr>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Runs the whole front end over `source`.
+///
+/// Parsing runs even when scanning fails, so the diagnostics hold every
+/// scan error followed by the parse error, if there is one. `ast` is the
+/// parsed expression, or `None` if parsing failed.
+///
+/// ```
+/// let result = rlox::compile("@ 1 +");
+/// assert_eq!(result.tokens.len(), 3);
+/// assert!(result.ast.is_none());
+/// assert_eq!(result.diagnostics.len(), 2);
+/// assert_eq!(result.diagnostics[0].message, "Unknown character");
+/// assert_eq!(result.diagnostics[1].message, "Expect expression.");
+/// ```
+pub fn compile(source: &str) -> CompileResult {
+    compile_with_config(source, &Config::default())
+}
+
+/// `compile` with the scanner and parser options in `config`.
+///
+/// ```
+/// use rlox::config::Config;
+///
+/// assert!(rlox::compile(".5").ast.is_none());
+///
+/// let config = Config { allow_leading_dot_numbers: true, ..Config::default() };
+/// assert!(rlox::compile_with_config(".5", &config).ast.is_some());
+/// ```
+pub fn compile_with_config(source: &str, config: &Config) -> CompileResult {
+    scan_with_config(source, config).parse()
+}
+
+/// The tokens and scan errors from compile's first phase, for callers that
+/// time the phases separately. Parsing consumes it, so a scan is parsed
+/// once, with the config it was scanned with.
+///
+/// ```
+/// let scanned = rlox::scan("@ 1 +");
+/// assert_eq!(scanned.tokens.len(), 3);
+/// assert_eq!(scanned.diagnostics.len(), 1);
+///
+/// let result = scanned.parse();
+/// assert_eq!(result.diagnostics.len(), 2);
+/// ```
+pub struct Scanned {
+    pub tokens: Vec<scanner::Token>,
+    pub diagnostics: Vec<Diagnostic>,
+    config: Config,
+}
+
+/// Scans `source` without parsing it.
+pub fn scan(source: &str) -> Scanned {
+    scan_with_config(source, &Config::default())
+}
+
+/// `scan` with the scanner and parser options in `config`.
+pub fn scan_with_config(source: &str, config: &Config) -> Scanned {
+    let mut scanner = scanner::Scanner::from_source_with_config(source, config);
+    scanner.scan_tokens();
+    let diagnostics = scanner.errors.iter().map(Diagnostic::from).collect();
+    Scanned { tokens: scanner.tokens, diagnostics, config: config.clone() }
+}
+
+impl Scanned {
+    pub fn parse(self) -> CompileResult {
+        let Scanned { tokens, mut diagnostics, config } = self;
+        let ast = match ast::Parser::with_config(&tokens, &config).parse() {
+            Ok(expr) => Some(expr),
+            Err(error) => {
+                diagnostics.push(Diagnostic::from(&error));
+                None
+            }
+        };
+        CompileResult { tokens, ast, diagnostics }
+    }
+}
+
+/// Scans and parses `source` as one expression, failing with every
+/// diagnostic from either phase.
+///
+/// ```
+/// let errors = rlox::parse("@ 1 +").unwrap_err();
+/// assert_eq!(errors.len(), 2);
+///
+/// assert!(rlox::parse("1 + 2").is_ok());
+/// ```
+pub fn parse(source: &str) -> Result<ast::Expr, Vec<Diagnostic>> {
+    parse_with_config(source, &Config::default())
+}
+
+/// `parse` with the scanner and parser options in `config`.
+pub fn parse_with_config(source: &str, config: &Config) -> Result<ast::Expr, Vec<Diagnostic>> {
+    let result = compile_with_config(source, config);
+    match result.ast {
+        Some(expr) if result.diagnostics.is_empty() => Ok(expr),
+        _ => Err(result.diagnostics),
+    }
+}
+
+pub struct RunResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Runs `source` without touching the process, capturing what the `rlox`
+/// binary would print and the status it would exit with.
+///
+/// ```
+/// let result = rlox::run_to_string("1 + 2");
+/// assert_eq!(result.exit_code, 0);
+/// assert_eq!(result.stderr, "");
+///
+/// let result = rlox::run_to_string("1 +");
+/// assert_eq!(result.exit_code, 65);
+/// assert!(result.stderr.starts_with("[line 1] Error at end\n"));
+/// ```
+pub fn run_to_string(source: &str) -> RunResult {
+    match parse(source) {
+        Ok(_) => RunResult {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+        },
+        Err(errors) => {
+            let mut stderr = String::new();
+            for error in &errors {
+                stderr.push_str(&error.format(source, false));
+                stderr.push('\n');
+            }
+            // EX_DATAERR, matching the binary's exit status for bad input.
+            RunResult {
+                stdout: String::new(),
+                stderr,
+                exit_code: 65,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_reaches_both_phases() {
+        let leading_dot = Config { allow_leading_dot_numbers: true, ..Config::default() };
+        assert!(parse(".5 + 1").is_err());
+        assert!(parse_with_config(".5 + 1", &leading_dot).is_ok());
+        assert_eq!(scanner::tokenize_with_config(".5", &leading_dot).unwrap()[0].token_type,
+                   scanner::TokenType::Number(0.5));
+
+        let shallow = Config { max_depth: 2, ..Config::default() };
+        assert!(parse("((1))").is_ok());
+        let errors = parse_with_config("((1))", &shallow).unwrap_err();
+        assert_eq!(errors[0].message, "Too deeply nested.");
+
+        // A scan is parsed with the config it was scanned with.
+        let result = scan_with_config("((1))", &shallow).parse();
+        assert_eq!(result.diagnostics[0].message, "Too deeply nested.");
+    }
+}
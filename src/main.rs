@@ -1,57 +1,337 @@
 use std::fs::File;
 use std::io::prelude::*;
-use std::io;
+use std::io::{self, IsTerminal};
 use std::env;
 use std::path::Path;
 use std::process;
+use std::time::Instant;
 
-mod scanner;
-mod ast;
+use rlox::{ast, diagnostics, printer, scanner};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Run,
+    Tokens,
+    Ast,
+    Rpn,
+    Dot,
+    #[cfg(feature = "serde")]
+    AstJson,
+}
+
+// Exit statuses follow the BSD sysexits convention used by the book.
+#[derive(Clone, Copy, PartialEq)]
+enum Failure {
+    Usage,
+    Syntax,
+    Io,
+}
+
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Usage => 64,
+            Failure::Syntax => 65,
+            Failure::Io => 66,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Options {
+    mode: Mode,
+    verbose: bool,
+    time: bool,
+    color: bool,
+}
+
+#[cfg(not(feature = "serde"))]
+const MODES: &str = "run | tokens | ast | --tokens | --ast | --rpn | --dot";
+#[cfg(feature = "serde")]
+const MODES: &str = "run | tokens | ast | --tokens | --ast | --rpn | --dot | --ast-json";
+
+fn usage() -> String {
+    format!("usage: rlox [--verbose] [--time] [--no-color] [{}] [-e | --eval <source> | <file> | -]
+       rlox [--verbose] [--time] [--no-color] repl", MODES)
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        println!("usage: rlox <file>");
-        process::exit(64);
-    } else if args.len() == 2 {
-        run_file(&args[1])
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        mode: Mode::Run,
+        verbose: false,
+        time: false,
+        color: diagnostics::use_color(),
+    };
+    if !take_flags(&mut args, &mut options) {
+        return;
+    }
+
+    // Subcommands name the same modes as the older flags.
+    let mode = match args.first().map(String::as_str) {
+        Some("run") => Some(Mode::Run),
+        Some("tokens") | Some("--tokens") => Some(Mode::Tokens),
+        Some("ast") | Some("--ast") => Some(Mode::Ast),
+        Some("--rpn") => Some(Mode::Rpn),
+        Some("--dot") => Some(Mode::Dot),
+        #[cfg(feature = "serde")]
+        Some("--ast-json") => Some(Mode::AstJson),
+        _ => None,
+    };
+    // After a mode, "repl" is just a file name.
+    let repl = mode.is_none() && args.first().map(String::as_str) == Some("repl");
+    if mode.is_some() || repl {
+        options.mode = mode.unwrap_or(Mode::Run);
+        args.remove(0);
+        // Global flags may also follow the subcommand.
+        if !take_flags(&mut args, &mut options) {
+            return;
+        }
+    }
+
+    let first = args.first().map(String::as_str);
+    let result = if repl {
+        if args.is_empty() {
+            run_prompt(options);
+            Ok(())
+        } else {
+            Err(Failure::Usage)
+        }
+    } else if first == Some("-e") || first == Some("--eval") {
+        if args.len() == 2 {
+            run(&args[1], options)
+        } else {
+            Err(Failure::Usage)
+        }
+    } else if args.len() > 1 {
+        Err(Failure::Usage)
+    } else if args.len() == 1 {
+        run_file(&args[0], options)
+    } else if !io::stdin().is_terminal() {
+        run_file("-", options)
+    } else if mode.is_none() {
+        run_prompt(options);
+        Ok(())
     } else {
-        run_prompt();
+        // A mode wants a program, and there's none to read.
+        Err(Failure::Usage)
+    };
+
+    if let Err(failure) = result {
+        if failure == Failure::Usage {
+            eprintln!("{}", usage());
+        }
+        process::exit(failure.exit_code());
     }
 }
 
-fn run_file(file: &String) {
-    let path = Path::new(file);
-    let mut file = File::open(&path)
-        .expect("Failed to open file");
+// Takes the global flags off the front of `args`. Returns false once --help
+// or --version has printed and there's nothing left to do.
+fn take_flags(args: &mut Vec<String>, options: &mut Options) -> bool {
+    while let Some(flag) = args.first() {
+        match flag.as_str() {
+            "--verbose" => options.verbose = true,
+            "--time" => options.time = true,
+            "--no-color" => options.color = false,
+            "--version" | "-V" => {
+                println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                return false;
+            },
+            "--help" | "-h" => {
+                println!("{}", usage());
+                return false;
+            },
+            _ => break,
+        }
+        args.remove(0);
+    }
+    true
+}
+
+fn run_file(file: &str, options: Options) -> Result<(), Failure> {
+    if options.verbose {
+        eprintln!("rlox: reading {}", if file == "-" { "standard input" } else { file });
+    }
+
+    let bytes = match read_source(file) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("rlox: cannot open '{}': {}", file, error);
+            return Err(Failure::Io);
+        }
+    };
+
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!("rlox: '{}': input is not valid UTF-8", file);
+            return Err(Failure::Syntax);
+        }
+    };
 
-    let mut source = String::new();
-    file.read_to_string(&mut source)
-        .expect("Failed to read file");
+    // A BOM comes before everything, the shebang included.
+    let source = source.strip_prefix('\u{FEFF}').unwrap_or(&source);
+    run(strip_shebang(source), options)
+}
 
-    let source: Vec<char> = source.chars().collect();
-    run(source);
+// Lets scripts start with "#!/usr/bin/env rlox". The line break is kept so
+// later lines keep their numbers.
+fn strip_shebang(source: &str) -> &str {
+    if !source.starts_with("#!") {
+        return source;
+    }
+    let end = source.find(['\n', '\r']).unwrap_or(source.len());
+    &source[end..]
 }
 
-fn run_prompt() {
+fn read_source(file: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if file == "-" {
+        io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        let path = Path::new(file);
+        let mut file = File::open(path)?;
+        file.read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+fn run_prompt(options: Options) {
+    let mut buffer = String::new();
     loop {
-        print!("> ");
+        if buffer.is_empty() {
+            print!("> ");
+        } else {
+            print!("... ");
+        }
         io::stdout().flush()
             .expect("Failed to flush output");
 
         let mut line = String::new();
-        io::stdin().read_line(&mut line)
+        let read = io::stdin().read_line(&mut line)
             .expect("Failed to read line");
-        let line: Vec<char> = line.chars().collect();
-        run(line);
+        if read == 0 {
+            println!();
+            break;
+        }
+        if buffer.is_empty() && (line.trim() == "exit" || line.trim() == ":q") {
+            break;
+        }
+        buffer.push_str(&line);
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            continue;
+        }
+
+        let start = Instant::now();
+        let mut scanner: scanner::Scanner = scanner::Scanner::from_source(&buffer);
+        scanner.scan_tokens();
+        let scanned = Instant::now();
+
+        let mut parser: ast::Parser = ast::Parser::new(&scanner.tokens);
+        let result = parser.parse();
+        let parsed = Instant::now();
+
+        // Keep reading lines while the input ran out mid-expression or inside
+        // a string. A blank line gives up on the entry and reports the error.
+        let open_string = !scanner.errors.is_empty()
+            && scanner.errors.iter().all(|error| is_open_string(error, &buffer));
+        let incomplete = match &result {
+            Err(error) => open_string || (error.is_at_end() && scanner.errors.is_empty() && scanner.tokens.len() > 1),
+            Ok(_) => open_string,
+        };
+        if incomplete && !line.trim().is_empty() {
+            continue;
+        }
+
+        if options.time {
+            eprintln!("rlox: scan took {:?}", scanned - start);
+            eprintln!("rlox: parse took {:?}", parsed - scanned);
+        }
+        report_scan_errors(&buffer, &scanner.errors, options.color);
+        match result {
+            Ok(expr) => println!("{}", printer::print_ast(&expr)),
+            Err(error) => report(&buffer, &error, options.color),
+        }
+        buffer.clear();
     }
 }
 
-fn run(source: Vec<char>) {
-    let mut scanner: scanner::Scanner = scanner::Scanner::new(source);
+// A string, raw string or interpolation still open when the input ran out.
+fn is_open_string(error: &scanner::ScanError, source: &str) -> bool {
+    let unterminated = matches!(error.message,
+                                "Unterminated string." |
+                                "Unterminated raw string." |
+                                "Unterminated string interpolation.");
+    unterminated && error.span.1 == source.len()
+}
+
+fn run(source: &str, options: Options) -> Result<(), Failure> {
+    if options.mode == Mode::Tokens {
+        return run_tokens(source, options);
+    }
+
+    let start = Instant::now();
+    let scan = rlox::scan(source);
+    let scanned = Instant::now();
+    let result = scan.parse();
+    let parsed = Instant::now();
+    if options.time {
+        eprintln!("rlox: scan took {:?}", scanned - start);
+        eprintln!("rlox: parse took {:?}", parsed - scanned);
+    }
+    if options.verbose {
+        eprintln!("rlox: {} tokens, {} diagnostics", result.tokens.len(), result.diagnostics.len());
+    }
+    for diagnostic in &result.diagnostics {
+        diagnostics::report(source, diagnostic, options.color);
+    }
+
+    if let Some(expr) = &result.ast {
+        match options.mode {
+            Mode::Ast => println!("{}", printer::print_ast(expr)),
+            Mode::Rpn => println!("{}", printer::rpn(expr)),
+            Mode::Dot => print!("{}", printer::to_dot(expr)),
+            #[cfg(feature = "serde")]
+            Mode::AstJson => println!("{}", ast::ast_to_json(expr)),
+            _ => (),
+        }
+    }
+
+    if !result.diagnostics.is_empty() {
+        return Err(Failure::Syntax);
+    }
+    Ok(())
+}
+
+// Token dumps only scan, so a stream that doesn't parse still prints cleanly.
+fn run_tokens(source: &str, options: Options) -> Result<(), Failure> {
+    let start = Instant::now();
+    let mut scanner: scanner::Scanner = scanner::Scanner::from_source(source);
     scanner.scan_tokens();
-    println!("{:?}", scanner.tokens);
+    if options.time {
+        eprintln!("rlox: scan took {:?}", start.elapsed());
+    }
+    if options.verbose {
+        eprintln!("rlox: {} tokens, {} diagnostics", scanner.tokens.len(), scanner.errors.len());
+    }
+    report_scan_errors(source, &scanner.errors, options.color);
+    for token in &scanner.tokens {
+        println!("{:?}", token);
+    }
+
+    if !scanner.errors.is_empty() {
+        return Err(Failure::Syntax);
+    }
+    Ok(())
+}
+
+fn report_scan_errors(source: &str, errors: &[scanner::ScanError], color: bool) {
+    for error in errors {
+        diagnostics::report(source, &error.into(), color);
+    }
+}
 
-    let mut parser: ast::Parser = ast::Parser::new(scanner.tokens);
-    println!("{:?}", parser.parse());
+fn report(source: &str, error: &ast::ParseError, color: bool) {
+    diagnostics::report(source, &error.into(), color);
 }
@@ -0,0 +1,180 @@
+use crate::ast::{Expr, Visitor};
+use crate::scanner;
+
+pub fn print_ast(expr: &Expr) -> String {
+    expr.accept(&mut AstPrinter)
+}
+
+pub struct AstPrinter;
+
+impl AstPrinter {
+    fn parenthesize(&mut self, name: &str, exprs: &[&Expr]) -> String {
+        let mut out = String::from("(");
+        out.push_str(name);
+        for expr in exprs {
+            out.push(' ');
+            out.push_str(&expr.accept(self));
+        }
+        out.push(')');
+        out
+    }
+}
+
+impl Visitor<String> for AstPrinter {
+    fn visit_binary(&mut self, left: &Expr, operator: &scanner::Token, right: &Expr) -> String {
+        self.parenthesize(&operator.lexeme, &[left, right])
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> String {
+        self.parenthesize("group", &[expr])
+    }
+
+    fn visit_literal(&mut self, value: &scanner::Token) -> String {
+        literal(value)
+    }
+
+    fn visit_unary(&mut self, operator: &scanner::Token, right: &Expr) -> String {
+        self.parenthesize(&operator.lexeme, &[right])
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> String {
+        let parts: Vec<&Expr> = parts.iter().collect();
+        self.parenthesize("interpolate", &parts)
+    }
+
+    fn visit_array(&mut self, elements: &[Expr]) -> String {
+        let elements: Vec<&Expr> = elements.iter().collect();
+        self.parenthesize("array", &elements)
+    }
+
+    fn visit_index(&mut self, target: &Expr, index: &Expr) -> String {
+        self.parenthesize("index", &[target, index])
+    }
+}
+
+pub fn rpn(expr: &Expr) -> String {
+    expr.accept(&mut RpnPrinter)
+}
+
+pub struct RpnPrinter;
+
+impl Visitor<String> for RpnPrinter {
+    fn visit_binary(&mut self, left: &Expr, operator: &scanner::Token, right: &Expr) -> String {
+        format!("{} {} {}", left.accept(self), right.accept(self), operator.lexeme)
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn visit_literal(&mut self, value: &scanner::Token) -> String {
+        literal(value)
+    }
+
+    // Unary minus is spelled "neg" so "1 neg" can't be mistaken for a subtraction.
+    fn visit_unary(&mut self, operator: &scanner::Token, right: &Expr) -> String {
+        let name = match operator.token_type {
+            scanner::TokenType::Minus => "neg",
+            _ => &operator.lexeme,
+        };
+        format!("{} {}", right.accept(self), name)
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expr]) -> String {
+        let mut out = String::new();
+        for part in parts {
+            out.push_str(&part.accept(self));
+            out.push(' ');
+        }
+        out.push_str("interpolate");
+        out
+    }
+
+    fn visit_array(&mut self, elements: &[Expr]) -> String {
+        let mut out = String::new();
+        for element in elements {
+            out.push_str(&element.accept(self));
+            out.push(' ');
+        }
+        out.push_str("array");
+        out
+    }
+
+    fn visit_index(&mut self, target: &Expr, index: &Expr) -> String {
+        format!("{} {} index", target.accept(self), index.accept(self))
+    }
+}
+
+pub fn to_dot(expr: &Expr) -> String {
+    let mut out = String::from("digraph ast {\n");
+    let mut next_id = 0;
+    dot_node(expr, &mut next_id, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+fn dot_node(expr: &Expr, next_id: &mut usize, out: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+
+    let (label, children): (String, Vec<&Expr>) = match expr {
+        Expr::Binary(left, operator, right) => (format!("Binary {}", operator.lexeme), vec![left, right]),
+        Expr::Grouping(expr) => (String::from("Grouping"), vec![expr]),
+        Expr::Literal(token) => (format!("Literal {}", literal(token)), vec![]),
+        Expr::Unary(operator, right) => (format!("Unary {}", operator.lexeme), vec![right]),
+        Expr::Interpolation(parts) => (String::from("Interpolation"), parts.iter().collect()),
+        Expr::Array(elements) => (String::from("Array"), elements.iter().collect()),
+        Expr::Index(target, index) => (String::from("Index"), vec![target, index]),
+    };
+
+    let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+    out.push_str(&format!("    n{} [label=\"{}\"];\n", id, label));
+    for child in children {
+        let child_id = dot_node(child, next_id, out);
+        out.push_str(&format!("    n{} -> n{};\n", id, child_id));
+    }
+
+    id
+}
+
+fn literal(token: &scanner::Token) -> String {
+    match &token.token_type {
+        scanner::TokenType::Number(n) => n.to_string(),
+        scanner::TokenType::String(s) => s.clone(),
+        scanner::TokenType::Interpolation(s) => s.clone(),
+        _ => token.lexeme.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpn_of(source: &str) -> String {
+        rpn(&crate::parse(source).unwrap())
+    }
+
+    #[test]
+    fn rpn_puts_operands_first() {
+        assert_eq!(rpn_of("1 + 2 * 3"), "1 2 3 * +");
+        assert_eq!(rpn_of("1 - 2 - 3"), "1 2 - 3 -");
+    }
+
+    #[test]
+    fn rpn_drops_grouping() {
+        assert_eq!(rpn_of("(1 + 2) * 3"), "1 2 + 3 *");
+        assert_eq!(rpn_of("((1))"), "1");
+    }
+
+    #[test]
+    fn rpn_nested_expressions() {
+        assert_eq!(rpn_of("(1 + 2) * (3 - 4) / 5"), "1 2 + 3 4 - * 5 /");
+        assert_eq!(rpn_of("!(1 == (2 + 3))"), "1 2 3 + == !");
+    }
+
+    #[test]
+    fn rpn_unary_minus_is_distinct() {
+        assert_eq!(rpn_of("-1 - 2"), "1 neg 2 -");
+        assert_eq!(rpn_of("1 - -2"), "1 2 neg -");
+    }
+}
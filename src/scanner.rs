@@ -1,25 +1,36 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Clone, Debug)]
+use crate::config::Config;
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: u32,
+    pub span: (usize, usize),
 }
 
 impl Token {
-    fn new(token_type: TokenType, lexeme: String, line: u32) -> Token {
-        Token { token_type, lexeme, line }
+    fn new(token_type: TokenType, lexeme: String, line: u32, span: (usize, usize)) -> Token {
+        Token { token_type, lexeme, line, span }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -27,6 +38,10 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
 
     Bang,
     BangEqual,
@@ -36,14 +51,25 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    MinusEqual,
+    PlusEqual,
+    SlashEqual,
+    StarEqual,
+    StarStar,
 
-    Identifier(String),
+    Identifier(Rc<str>),
     String(String),
+    Interpolation(String),
     Number(f64),
+    Char(char),
 
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -62,74 +88,364 @@ pub enum TokenType {
     EOF
 }
 
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenType::LeftParen => write!(f, "("),
+            TokenType::RightParen => write!(f, ")"),
+            TokenType::LeftBrace => write!(f, "{{"),
+            TokenType::RightBrace => write!(f, "}}"),
+            TokenType::LeftBracket => write!(f, "["),
+            TokenType::RightBracket => write!(f, "]"),
+            TokenType::Comma => write!(f, ","),
+            TokenType::Dot => write!(f, "."),
+            TokenType::Minus => write!(f, "-"),
+            TokenType::Plus => write!(f, "+"),
+            TokenType::Semicolon => write!(f, ";"),
+            TokenType::Slash => write!(f, "/"),
+            TokenType::Star => write!(f, "*"),
+            TokenType::Amp => write!(f, "&"),
+            TokenType::Pipe => write!(f, "|"),
+            TokenType::Caret => write!(f, "^"),
+            TokenType::Tilde => write!(f, "~"),
+
+            TokenType::Bang => write!(f, "!"),
+            TokenType::BangEqual => write!(f, "!="),
+            TokenType::Equal => write!(f, "="),
+            TokenType::EqualEqual => write!(f, "=="),
+            TokenType::Greater => write!(f, ">"),
+            TokenType::GreaterEqual => write!(f, ">="),
+            TokenType::Less => write!(f, "<"),
+            TokenType::LessEqual => write!(f, "<="),
+            TokenType::LessLess => write!(f, "<<"),
+            TokenType::GreaterGreater => write!(f, ">>"),
+            TokenType::MinusEqual => write!(f, "-="),
+            TokenType::PlusEqual => write!(f, "+="),
+            TokenType::SlashEqual => write!(f, "/="),
+            TokenType::StarEqual => write!(f, "*="),
+            TokenType::StarStar => write!(f, "**"),
+
+            TokenType::Identifier(name) => write!(f, "{}", name),
+            TokenType::String(s) => write!(f, "\"{}\"", s),
+            TokenType::Interpolation(s) => write!(f, "\"{}${{", s),
+            TokenType::Number(n) => write!(f, "{}", n),
+            TokenType::Char(c) => write!(f, "{:?}", c),
+
+            TokenType::And => write!(f, "and"),
+            TokenType::Break => write!(f, "break"),
+            TokenType::Class => write!(f, "class"),
+            TokenType::Continue => write!(f, "continue"),
+            TokenType::Else => write!(f, "else"),
+            TokenType::False => write!(f, "false"),
+            TokenType::Fun => write!(f, "fun"),
+            TokenType::For => write!(f, "for"),
+            TokenType::If => write!(f, "if"),
+            TokenType::Nil => write!(f, "nil"),
+            TokenType::Or => write!(f, "or"),
+            TokenType::Print => write!(f, "print"),
+            TokenType::Return => write!(f, "return"),
+            TokenType::Super => write!(f, "super"),
+            TokenType::This => write!(f, "this"),
+            TokenType::True => write!(f, "true"),
+            TokenType::Var => write!(f, "var"),
+            TokenType::While => write!(f, "while"),
+
+            TokenType::EOF => write!(f, "end of input"),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' at line {}", self.lexeme, self.line)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub line: u32,
+    pub span: (usize, usize),
+    pub message: &'static str,
+}
+
+/// Scans `source` in one call, returning every token or every scan error.
+///
+/// ```
+/// use rlox::scanner::{tokenize, TokenType};
+///
+/// let tokens = tokenize("1 + 2").unwrap();
+/// assert_eq!(tokens.len(), 4);
+/// assert_eq!(tokens[1].token_type, TokenType::Plus);
+///
+/// // Empty input still ends in EOF.
+/// let tokens = tokenize("").unwrap();
+/// assert_eq!(tokens.len(), 1);
+/// assert_eq!(tokens[0].token_type, TokenType::EOF);
+/// assert_eq!(tokens[0].lexeme, "");
+///
+/// let errors = tokenize("1 @ 2").unwrap_err();
+/// assert_eq!(errors[0].message, "Unknown character");
+///
+/// // A run of unknown characters is a single error spanning all of them.
+/// let errors = tokenize("@@@@").unwrap_err();
+/// assert_eq!(errors.len(), 1);
+/// assert_eq!(errors[0].span, (0, 4));
+/// ```
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<ScanError>> {
+    tokenize_with_config(source, &Config::default())
+}
+
+/// `tokenize` with the scanner options in `config`.
+pub fn tokenize_with_config(source: &str, config: &Config) -> Result<Vec<Token>, Vec<ScanError>> {
+    let mut scanner = Scanner::from_source_with_config(source, config);
+    scanner.scan_tokens();
+    if scanner.errors.is_empty() {
+        Ok(scanner.tokens)
+    } else {
+        Err(scanner.errors)
+    }
+}
+
+// The text being scanned. Text from Scanner::from_source is kept as bytes,
+// a quarter of the memory of chars, until a non-ASCII character comes within
+// reach of the scanner; from then on it is scanned as chars.
+enum Source {
+    Ascii { text: String, ascii_len: usize },
+    Chars(Vec<char>),
+}
+
+impl Source {
+    fn len(&self) -> usize {
+        match self {
+            Source::Ascii { text, .. } => text.len(),
+            Source::Chars(chars) => chars.len(),
+        }
+    }
+
+    fn at(&self, i: usize) -> char {
+        match self {
+            Source::Ascii { text, .. } => char::from(text.as_bytes()[i]),
+            Source::Chars(chars) => chars[i],
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> Cow<'_, str> {
+        match self {
+            Source::Ascii { text, .. } => Cow::Borrowed(&text[start..end]),
+            Source::Chars(chars) => Cow::Owned(chars[start..end].iter().collect()),
+        }
+    }
+}
+
 pub struct Scanner {
-    source: Vec<char>,
+    source: Source,
     pub tokens: Vec<Token>,
-    reserved: HashMap<String, TokenType>,
+    identifiers: HashSet<Rc<str>>,
+    interpolations: Vec<usize>,
     start: usize,
     current: usize,
+    start_byte: usize,
+    current_byte: usize,
+    start_line: u32,
     line: u32,
+    pub errors: Vec<ScanError>,
+    allow_leading_dot_numbers: bool,
+    pending: VecDeque<Result<Token, ScanError>>,
+    follows_value: bool,
+    finished: bool,
 }
 
 impl Scanner {
     pub fn new(source: Vec<char>) -> Scanner {
-        let mut reserved = HashMap::new();
-        reserved.insert(String::from("and"), TokenType::And);
-        reserved.insert(String::from("class"), TokenType::Class);
-        reserved.insert(String::from("else"), TokenType::Else);
-        reserved.insert(String::from("false"), TokenType::False);
-        reserved.insert(String::from("for"), TokenType::For);
-        reserved.insert(String::from("fun"), TokenType::Fun);
-        reserved.insert(String::from("if"), TokenType::If);
-        reserved.insert(String::from("nil"), TokenType::Nil);
-        reserved.insert(String::from("or"), TokenType::Or);
-        reserved.insert(String::from("print"), TokenType::Print);
-        reserved.insert(String::from("return"), TokenType::Return);
-        reserved.insert(String::from("super"), TokenType::Super);
-        reserved.insert(String::from("this"), TokenType::This);
-        reserved.insert(String::from("true"), TokenType::True);
-        reserved.insert(String::from("var"), TokenType::Var);
-        reserved.insert(String::from("while"), TokenType::While);
-
-        Scanner {
+        Scanner::with_config(source, &Config::default())
+    }
+
+    pub fn with_config(mut source: Vec<char>, config: &Config) -> Scanner {
+        // Editors on Windows sometimes save a byte order mark at the start.
+        if source.first() == Some(&'\u{FEFF}') {
+            source.remove(0);
+        }
+
+        Scanner::build(Source::Chars(source), config)
+    }
+
+    /// Like `Scanner::new`, but scans ASCII text as bytes rather than
+    /// collecting every character into a `Vec<char>` first. Source with
+    /// other characters falls back to chars from the first one onwards, so
+    /// the tokens are the same either way.
+    ///
+    /// ```
+    /// use rlox::scanner::Scanner;
+    ///
+    /// let mut scanner = Scanner::from_source("\"café\" + 1");
+    /// scanner.scan_tokens();
+    ///
+    /// let mut chars = Scanner::new("\"café\" + 1".chars().collect());
+    /// chars.scan_tokens();
+    /// assert_eq!(scanner.tokens, chars.tokens);
+    /// ```
+    pub fn from_source(source: &str) -> Scanner {
+        Scanner::from_source_with_config(source, &Config::default())
+    }
+
+    pub fn from_source_with_config(source: &str, config: &Config) -> Scanner {
+        let text = String::from(source.strip_prefix('\u{FEFF}').unwrap_or(source));
+        let ascii_len = text.bytes().position(|b| !b.is_ascii()).unwrap_or(text.len());
+        Scanner::build(Source::Ascii { text, ascii_len }, config)
+    }
+
+    fn build(source: Source, config: &Config) -> Scanner {
+        // Roughly one token per four characters of typical source.
+        let tokens = Vec::with_capacity(source.len() / 4 + 1);
+
+        let mut scanner = Scanner {
             source,
-            tokens: Vec::new(),
-            reserved,
+            tokens,
+            identifiers: HashSet::new(),
+            interpolations: Vec::new(),
             start: 0,
             current: 0,
-            line: 1
+            start_byte: 0,
+            current_byte: 0,
+            start_line: 1,
+            line: 1,
+            errors: Vec::new(),
+            allow_leading_dot_numbers: config.allow_leading_dot_numbers,
+            pending: VecDeque::new(),
+            follows_value: false,
+            finished: false,
+        };
+        scanner.fall_back_to_chars();
+        scanner
+    }
+
+    // Byte and char indexes agree up to the first non-ASCII character, so
+    // the switch to chars can happen any time before the scanner reads it.
+    // The furthest it reads is two past current, in raw_string.
+    fn fall_back_to_chars(&mut self) {
+        if let Source::Ascii { text, ascii_len } = &self.source {
+            if *ascii_len < text.len() && *ascii_len <= self.current + 2 {
+                let chars = text.chars().collect();
+                self.source = Source::Chars(chars);
+            }
         }
     }
 
     pub fn scan_tokens(&mut self) {
-        while !self.is_at_end() {
-            self.start = self.current;
+        while let Some(result) = self.next() {
+            match result {
+                Ok(token) => self.tokens.push(token),
+                Err(error) => self.errors.push(error),
+            }
+        }
+    }
+
+    // Scans one lexeme, which may produce no tokens (whitespace, comments),
+    // a token, errors, or, at the end of input, the final EOF token.
+    fn scan_next(&mut self) {
+        self.start = self.current;
+        self.start_byte = self.current_byte;
+        self.start_line = self.line;
+
+        if !self.is_at_end() {
             self.scan_token();
+            return;
         }
 
-        self.add_token(TokenType::EOF);
+        if !self.interpolations.is_empty() {
+            self.error("Unterminated string interpolation.");
+        }
+        // EOF is built here rather than sliced from the source by add_token,
+        // so it always has an empty lexeme and sits on the final line.
+        let end = self.current_byte;
+        self.pending.push_back(Ok(Token::new(TokenType::EOF, String::new(), self.line, (end, end))));
+        self.finished = true;
     }
 
     fn add_token(&mut self, token: TokenType) {
-        let lexeme = self.source[self.start..self.current].to_vec();
-        let lexeme = lexeme.iter().collect();
-        let token = Token::new(token, lexeme, self.line);
-        self.tokens.push(token);
+        // Whether this token can end an operand, making a following '.' a
+        // property access rather than the start of a number.
+        self.follows_value = matches!(token,
+                                      TokenType::Identifier(_) | TokenType::String(_) |
+                                      TokenType::Number(_) | TokenType::Char(_) |
+                                      TokenType::RightParen | TokenType::RightBracket |
+                                      TokenType::This |
+                                      TokenType::Super);
+
+        let lexeme = self.source.text(self.start, self.current).into_owned();
+        let token = Token::new(token, lexeme, self.start_line, (self.start_byte, self.current_byte));
+        self.pending.push_back(Ok(token));
     }
 
     fn scan_token(&mut self) {
         let c: char = self.advance();
+        if !Scanner::starts_token(c) {
+            // One error for a whole run, e.g. binary input.
+            while !self.is_at_end() && !Scanner::starts_token(self.peek()) {
+                self.advance();
+            }
+            self.error("Unknown character");
+            return;
+        }
+
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace);
+            },
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string();
+                },
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace);
+                },
+                None => self.add_token(TokenType::RightBrace),
+            },
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            '.' => {
+                if self.allow_leading_dot_numbers && Scanner::is_digit(self.peek()) && !self.follows_value {
+                    self.number();
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
+            },
+            '-' => {
+                if self.match_char('=') {
+                    self.add_token(TokenType::MinusEqual);
+                } else {
+                    self.add_token(TokenType::Minus);
+                }
+            },
+            '+' => {
+                if self.match_char('=') {
+                    self.add_token(TokenType::PlusEqual);
+                } else {
+                    self.add_token(TokenType::Plus);
+                }
+            },
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => {
+                if self.match_char('=') {
+                    self.add_token(TokenType::StarEqual);
+                } else if self.match_char('*') {
+                    self.add_token(TokenType::StarStar);
+                } else {
+                    self.add_token(TokenType::Star);
+                }
+            },
+            '&' => self.add_token(TokenType::Amp),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
             '!' => {
                 if self.match_char('=') {
                     self.add_token(TokenType::BangEqual);
@@ -147,6 +463,8 @@ impl Scanner {
             '<' => {
                 if self.match_char('=') {
                     self.add_token(TokenType::LessEqual);
+                } else if self.match_char('<') {
+                    self.add_token(TokenType::LessLess);
                 } else {
                     self.add_token(TokenType::Less);
                 }
@@ -154,42 +472,48 @@ impl Scanner {
             '>' => {
                 if self.match_char('=') {
                     self.add_token(TokenType::GreaterEqual);
+                } else if self.match_char('>') {
+                    self.add_token(TokenType::GreaterGreater);
                 } else {
                     self.add_token(TokenType::Greater);
                 }
             },
             '/' => {
                 if self.match_char('/') {
-                    while (self.peek() != '\n') && !self.is_at_end() {
+                    while !Scanner::is_line_break(self.peek()) && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.match_char('=') {
+                    self.add_token(TokenType::SlashEqual);
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             },
-            ' ' => return,
-            '\t' => return,
-            '\r' => return,
-            '\n' => {
+            ' ' | '\t' => (),
+            // "\r\n" counts once, on its '\n'.
+            '\r' => if self.peek() != '\n' {
                 self.line += 1;
-                return;
             },
+            '\n' => self.line += 1,
             '"' => self.string(),
+            '\'' => self.char_literal(),
+            // starts_token leaves only digits and letters for here.
             _   => {
                 if Scanner::is_digit(c) {
                     self.number();
-                } else if Scanner::is_alpha(c) {
-                    self.identifier();
                 } else {
-                    self.error("Unknown character");
+                    self.identifier();
                 }
             },
         }
     }
 
     fn advance(&mut self) -> char {
+        let c = self.source.at(self.current);
         self.current += 1;
-        self.source[self.current - 1]
+        self.current_byte += c.len_utf8();
+        self.fall_back_to_chars();
+        c
     }
 
     fn is_at_end(&self) -> bool {
@@ -201,20 +525,32 @@ impl Scanner {
             return false;
         }
 
-        if self.source[self.current] != expected {
+        if self.source.at(self.current) != expected {
             return false;
         }
 
         self.current += 1;
+        self.current_byte += expected.len_utf8();
+        self.fall_back_to_chars();
         true
     }
 
-    fn peek(&mut self) -> char {
+    fn peek(&self) -> char {
         if self.is_at_end() {
             return '\0';
         }
 
-        self.source[self.current]
+        self.source.at(self.current)
+    }
+
+    // True when the next character ends a line. "\r\n", "\n" and a bare "\r"
+    // each end exactly one.
+    fn at_line_break(&self) -> bool {
+        match self.peek() {
+            '\n' => true,
+            '\r' => self.peek_next() != '\n',
+            _ => false,
+        }
     }
 
     fn peek_next(&self) -> char {
@@ -222,12 +558,29 @@ impl Scanner {
             return '\0';
         }
 
-        return self.source[self.current + 1];
+        self.source.at(self.current + 1)
     }
 
+    // Scans one segment of a string literal. A segment starts at the opening
+    // quote or at the '}' closing an interpolation, and ends at the closing
+    // quote or at the next "${".
     fn string(&mut self) {
+        let opening = self.source.at(self.start) == '"';
+        if opening && self.peek() == '"' && self.peek_next() == '"' {
+            self.raw_string();
+            return;
+        }
+
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
+            if self.peek() == '$' && self.peek_next() == '{' {
+                let slice = self.source.text(self.start + 1, self.current).into_owned();
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                self.add_token(TokenType::Interpolation(slice));
+                return;
+            }
+            if self.at_line_break() {
                 self.line += 1;
             }
             self.advance();
@@ -239,21 +592,107 @@ impl Scanner {
         }
 
         self.advance();
-        let slice: Vec<char> = self.source[self.start + 1..self.current - 1].to_vec();
-        let slice: String = slice.iter().collect();
+        let slice = self.source.text(self.start + 1, self.current - 1).into_owned();
+        self.add_token(TokenType::String(slice));
+    }
+
+    // """...""" keeps its contents verbatim, newlines and backslashes included.
+    fn raw_string(&mut self) {
+        self.advance();
+        self.advance();
+
+        while !self.at_raw_string_end() {
+            if self.is_at_end() {
+                self.error("Unterminated raw string.");
+                return;
+            }
+            if self.at_line_break() {
+                self.line += 1;
+            }
+            self.advance();
+        }
+
+        self.advance();
+        self.advance();
+        self.advance();
+        let slice = self.source.text(self.start + 3, self.current - 3).into_owned();
         self.add_token(TokenType::String(slice));
     }
 
+    fn at_raw_string_end(&self) -> bool {
+        self.current + 3 <= self.source.len()
+            && (0..3).all(|i| self.source.at(self.current + i) == '"')
+    }
+
+    fn char_literal(&mut self) {
+        if self.peek() == '\'' {
+            self.advance();
+            self.error("Empty character literal.");
+            return;
+        }
+        if Scanner::is_line_break(self.peek()) || self.is_at_end() {
+            self.error("Unterminated character literal.");
+            return;
+        }
+
+        let mut c = self.advance();
+        if c == '\\' && !self.is_at_end() {
+            c = match self.advance() {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                '"' => '"',
+                other => {
+                    self.error("Unknown escape sequence.");
+                    other
+                }
+            };
+        }
+
+        if self.peek() != '\'' {
+            while self.peek() != '\'' && !Scanner::is_line_break(self.peek()) && !self.is_at_end() {
+                self.advance();
+            }
+            if self.peek() == '\'' {
+                self.advance();
+                self.error("Character literal must contain exactly one character.");
+            } else {
+                self.error("Unterminated character literal.");
+            }
+            return;
+        }
+
+        self.advance();
+        self.add_token(TokenType::Char(c));
+    }
+
+    // Whether c starts a token, whitespace or a comment. scan_token checks
+    // this before dispatching, so anything else is an unknown character.
+    fn starts_token(c: char) -> bool {
+        Scanner::is_digit(c) || Scanner::is_alpha(c) || "()[]{},.-+;*&|^~!=<>/\"' \t\r\n".contains(c)
+    }
+
+    fn is_line_break(c: char) -> bool {
+        c == '\n' || c == '\r'
+    }
+
     fn is_digit(c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
 
+    // A '.' only belongs to the number when a digit follows it, so "123.5" is
+    // one Number while "123.field" and a trailing "123." scan as Number, Dot.
+    // A number may also start at the '.', as in ".5".
     fn number(&mut self) {
+        let leading_dot = self.source.at(self.start) == '.';
         while Scanner::is_digit(self.peek()) {
             self.advance();
         }
 
-        if self.peek() == '.' && Scanner::is_digit(self.peek_next()) {
+        if !leading_dot && self.peek() == '.' && Scanner::is_digit(self.peek_next()) {
             self.advance();
 
             while Scanner::is_digit(self.peek()) {
@@ -261,9 +700,8 @@ impl Scanner {
             }
         }
 
-        let slice: Vec<char> = self.source[self.start..self.current].to_vec();
-        let slice: String = slice.iter().collect();
-        let digit: f64 = match slice.parse() {
+        let parsed = self.source.text(self.start, self.current).parse();
+        let digit: f64 = match parsed {
             Ok(d) => d,
             Err(_) => {
                 self.error("Failed to parse digit");
@@ -278,26 +716,348 @@ impl Scanner {
             self.advance();
         }
 
-        let slice: Vec<char> = self.source[self.start..self.current].to_vec();
-        let slice: String = slice.iter().collect();
+        let slice = self.source.text(self.start, self.current);
+        let token = match Scanner::keyword(&slice) {
+            Some(t) => t,
+            None => TokenType::Identifier(Scanner::intern(&mut self.identifiers, &slice)),
+        };
+        self.add_token(token);
+    }
 
-        match self.reserved.get(&slice) {
-            Some(t) => self.add_token(t.clone()),
-            None => self.add_token(TokenType::Identifier(slice)),
+    fn keyword(text: &str) -> Option<TokenType> {
+        match text {
+            "and" => Some(TokenType::And),
+            "break" => Some(TokenType::Break),
+            "class" => Some(TokenType::Class),
+            "continue" => Some(TokenType::Continue),
+            "else" => Some(TokenType::Else),
+            "false" => Some(TokenType::False),
+            "for" => Some(TokenType::For),
+            "fun" => Some(TokenType::Fun),
+            "if" => Some(TokenType::If),
+            "nil" => Some(TokenType::Nil),
+            "or" => Some(TokenType::Or),
+            "print" => Some(TokenType::Print),
+            "return" => Some(TokenType::Return),
+            "super" => Some(TokenType::Super),
+            "this" => Some(TokenType::This),
+            "true" => Some(TokenType::True),
+            "var" => Some(TokenType::Var),
+            "while" => Some(TokenType::While),
+            _ => None,
         }
     }
 
+    // Repeated identifiers share one allocation for their name.
+    fn intern(identifiers: &mut HashSet<Rc<str>>, name: &str) -> Rc<str> {
+        if let Some(interned) = identifiers.get(name) {
+            return Rc::clone(interned);
+        }
+
+        let interned: Rc<str> = Rc::from(name);
+        identifiers.insert(Rc::clone(&interned));
+        interned
+    }
+
+    // Identifiers follow Unicode letters rather than just ASCII, so "café" and
+    // "Δ" are names while symbols such as emoji are not.
     fn is_alpha(c: char) -> bool {
-        (c >= 'a' && c <= 'z') ||
-        (c >= 'A' && c <= 'Z') ||
-        c == '_'
+        c.is_alphabetic() || c == '_'
     }
 
     fn is_alpha_numeric(c: char) -> bool {
-        Scanner::is_alpha(c) || Scanner::is_digit(c)
+        Scanner::is_alpha(c) || c.is_alphanumeric()
+    }
+
+    // Like tokens, errors are reported at the line their lexeme starts on, so
+    // an unterminated string names the line it was opened on.
+    fn error(&mut self, message: &'static str) {
+        self.pending.push_back(Err(ScanError {
+            line: self.start_line,
+            span: (self.start_byte, self.current_byte),
+            message,
+        }));
+    }
+}
+
+// Tokens are scanned lazily, so a consumer can stop early without scanning
+// the rest of the source. The last item is always the EOF token.
+impl Iterator for Scanner {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.finished {
+            self.scan_next();
+        }
+        self.pending.pop_front()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(source: &str) -> Vec<TokenType> {
+        tokenize(source).unwrap().into_iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let tokens = tokenize("a + b").unwrap();
+        assert_eq!(tokens[1].token_type, TokenType::Plus);
+        assert_eq!(tokens[1].span, (2, 3));
+        assert_eq!(tokens[2].span, (4, 5));
 
-    fn error(&self, message: &str) {
-        println!("Error at line {}, {}", self.line, message);
+        // "é" is two bytes in UTF-8.
+        let tokens = tokenize("\"é\" + b").unwrap();
+        assert_eq!(tokens[0].span, (0, 4));
+        assert_eq!(tokens[1].span, (5, 6));
+    }
+
+    #[test]
+    fn repeated_identifiers_share_one_name() {
+        let names: Vec<Rc<str>> = tokenize("count + count * total - count").unwrap().into_iter()
+            .filter_map(|token| match token.token_type {
+                TokenType::Identifier(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names.len(), 4);
+        assert!(Rc::ptr_eq(&names[0], &names[1]));
+        assert!(Rc::ptr_eq(&names[0], &names[3]));
+        assert!(!Rc::ptr_eq(&names[0], &names[2]));
+    }
+
+    #[test]
+    fn keywords_resolve() {
+        let keywords = [
+            ("and", TokenType::And), ("break", TokenType::Break), ("class", TokenType::Class),
+            ("continue", TokenType::Continue), ("else", TokenType::Else), ("false", TokenType::False),
+            ("for", TokenType::For), ("fun", TokenType::Fun), ("if", TokenType::If),
+            ("nil", TokenType::Nil), ("or", TokenType::Or), ("print", TokenType::Print),
+            ("return", TokenType::Return), ("super", TokenType::Super), ("this", TokenType::This),
+            ("true", TokenType::True), ("var", TokenType::Var), ("while", TokenType::While),
+        ];
+        for (text, token_type) in keywords {
+            assert_eq!(types(text), vec![token_type, TokenType::EOF], "{}", text);
+        }
+    }
+
+    #[test]
+    fn keyword_prefixes_are_identifiers() {
+        assert_eq!(types("forge"), vec![TokenType::Identifier(Rc::from("forge")), TokenType::EOF]);
+        assert_eq!(types("orchid"), vec![TokenType::Identifier(Rc::from("orchid")), TokenType::EOF]);
+    }
+
+    fn messages(source: &str) -> Vec<&'static str> {
+        tokenize(source).unwrap_err().into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(types("'a'"), vec![TokenType::Char('a'), TokenType::EOF]);
+        assert_eq!(types("'\\n'"), vec![TokenType::Char('\n'), TokenType::EOF]);
+        assert_eq!(types("'\\''"), vec![TokenType::Char('\''), TokenType::EOF]);
+    }
+
+    #[test]
+    fn bad_char_literals() {
+        assert_eq!(messages("''"), vec!["Empty character literal."]);
+        assert_eq!(messages("'ab'"), vec!["Character literal must contain exactly one character."]);
+        assert_eq!(messages("'a"), vec!["Unterminated character literal."]);
+        assert_eq!(messages("'\\q'"), vec!["Unknown escape sequence."]);
+    }
+
+    #[test]
+    fn raw_strings_are_verbatim() {
+        let tokens = tokenize("\"\"\"a\\n\nb\\\"\"\"").unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::String(String::from("a\\n\nb\\")));
+    }
+
+    #[test]
+    fn raw_strings_count_lines() {
+        let tokens = tokenize("\"\"\"one\ntwo\r\nthree\"\"\" x").unwrap();
+        assert_eq!(tokens[0].line, 1);
+        assert_eq!(tokens[1].line, 3);
+        assert_eq!(tokens[2].token_type, TokenType::EOF);
+        assert_eq!(tokens[2].line, 3);
+    }
+
+    #[test]
+    fn interpolation_tokens() {
+        assert_eq!(types("\"x is ${1 + 1}\""), vec![
+            TokenType::Interpolation(String::from("x is ")),
+            TokenType::Number(1.0), TokenType::Plus, TokenType::Number(1.0),
+            TokenType::String(String::new()),
+            TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn interpolation_balances_braces() {
+        assert_eq!(types("\"a${ {} }b\""), vec![
+            TokenType::Interpolation(String::from("a")),
+            TokenType::LeftBrace, TokenType::RightBrace,
+            TokenType::String(String::from("b")),
+            TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn dot_after_number() {
+        assert_eq!(types("123.5"), vec![TokenType::Number(123.5), TokenType::EOF]);
+        assert_eq!(types("123.field"), vec![
+            TokenType::Number(123.0), TokenType::Dot, TokenType::Identifier(Rc::from("field")), TokenType::EOF,
+        ]);
+        assert_eq!(types("123."), vec![TokenType::Number(123.0), TokenType::Dot, TokenType::EOF]);
+    }
+
+    #[test]
+    fn leading_dot_numbers() {
+        let config = Config { allow_leading_dot_numbers: true, ..Config::default() };
+        let types = |source| -> Vec<TokenType> {
+            tokenize_with_config(source, &config).unwrap().into_iter().map(|token| token.token_type).collect()
+        };
+        assert_eq!(types(".5 + .5"), vec![
+            TokenType::Number(0.5), TokenType::Plus, TokenType::Number(0.5), TokenType::EOF,
+        ]);
+        assert_eq!(types(".5 == 0.5"), vec![
+            TokenType::Number(0.5), TokenType::EqualEqual, TokenType::Number(0.5), TokenType::EOF,
+        ]);
+
+        // After a value, '.' is member access.
+        assert_eq!(types("x.5"), vec![
+            TokenType::Identifier(Rc::from("x")), TokenType::Dot, TokenType::Number(5.0), TokenType::EOF,
+        ]);
+        assert_eq!(types("(1).5"), vec![
+            TokenType::LeftParen, TokenType::Number(1.0), TokenType::RightParen,
+            TokenType::Dot, TokenType::Number(5.0), TokenType::EOF,
+        ]);
+        assert_eq!(types("[1].5"), vec![
+            TokenType::LeftBracket, TokenType::Number(1.0), TokenType::RightBracket,
+            TokenType::Dot, TokenType::Number(5.0), TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        assert_eq!(types("café"), vec![TokenType::Identifier(Rc::from("café")), TokenType::EOF]);
+        assert_eq!(types("Δ"), vec![TokenType::Identifier(Rc::from("Δ")), TokenType::EOF]);
+        assert_eq!(types("_x1"), vec![TokenType::Identifier(Rc::from("_x1")), TokenType::EOF]);
+        assert_eq!(messages("🦀"), vec!["Unknown character"]);
+    }
+
+    #[test]
+    fn bom_is_skipped() {
+        let source = "var x = 1 +\n\"two\"";
+        let with_bom = format!("\u{FEFF}{}", source);
+        assert_eq!(tokenize(&with_bom).unwrap(), tokenize(source).unwrap());
+    }
+
+    #[test]
+    fn multi_line_string_has_its_opening_line() {
+        let tokens = tokenize("1\n\"two\nlines\" x").unwrap();
+        assert_eq!(tokens[1].token_type, TokenType::String(String::from("two\nlines")));
+        assert_eq!(tokens[1].line, 2);
+        assert_eq!(tokens[2].line, 3);
+
+        let errors = tokenize("1\n\"open\nstring").unwrap_err();
+        assert_eq!(errors[0].message, "Unterminated string.");
+        assert_eq!(errors[0].line, 2);
+    }
+
+    fn scan_both(source: &str) -> (Scanner, Scanner) {
+        let mut bytes = Scanner::from_source(source);
+        bytes.scan_tokens();
+        let mut chars = Scanner::new(source.chars().collect());
+        chars.scan_tokens();
+        (bytes, chars)
+    }
+
+    #[test]
+    fn ascii_source_stays_bytes() {
+        for source in ["var x = \"\"\"raw\"\"\" + 'c' // done", "\u{FEFF}1 + x"] {
+            let (bytes, chars) = scan_both(source);
+            assert!(matches!(bytes.source, Source::Ascii { .. }), "{:?}", source);
+            assert_eq!(bytes.tokens, chars.tokens, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn non_ascii_falls_back_to_chars() {
+        let sources = [
+            "é", "\"naïve\" + 1", "1 + café * 2", "\"\"\"\"é\"\"\"",
+            "\"\"é\"\"", "1 // ünïcode\n2", "x 🦀 y", "'é' + \"${\"ü\"}\"", "1 + 2 +é",
+        ];
+        for source in sources {
+            let (bytes, chars) = scan_both(source);
+            assert!(matches!(bytes.source, Source::Chars(_)), "{:?}", source);
+            assert_eq!(bytes.tokens, chars.tokens, "{:?}", source);
+            assert_eq!(bytes.errors, chars.errors, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn iterator_scans_lazily() {
+        let mut scanner = Scanner::from_source("1 + 2 * 3 - 4");
+        let first: Vec<TokenType> = scanner.by_ref().take(3).map(|token| token.unwrap().token_type).collect();
+        assert_eq!(first, vec![TokenType::Number(1.0), TokenType::Plus, TokenType::Number(2.0)]);
+
+        // Nothing past "1 + 2" has been read.
+        assert_eq!(scanner.current, 5);
+        assert!(scanner.pending.is_empty());
+        assert!(!scanner.finished);
+        assert_eq!(scanner.next().unwrap().unwrap().token_type, TokenType::Star);
+    }
+
+    #[test]
+    fn shift_tokens() {
+        assert_eq!(types("<< >> <= >= < >"), vec![
+            TokenType::LessLess, TokenType::GreaterGreater, TokenType::LessEqual,
+            TokenType::GreaterEqual, TokenType::Less, TokenType::Greater, TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn mixed_line_endings_count_once_each() {
+        let tokens = tokenize("a\r\nb\nc\rd\r\n\re // x\r\"s\r\nt\" f").unwrap();
+        let lines: Vec<u32> = tokens.iter().map(|token| token.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4, 6, 7, 8, 8]);
+    }
+
+    #[test]
+    fn trailing_dot_is_its_own_token() {
+        assert_eq!(types("123."), vec![TokenType::Number(123.0), TokenType::Dot, TokenType::EOF]);
+        assert_eq!(types("123.foo"), vec![
+            TokenType::Number(123.0), TokenType::Dot, TokenType::Identifier(Rc::from("foo")), TokenType::EOF,
+        ]);
+        assert_eq!(types("123.456"), vec![TokenType::Number(123.456), TokenType::EOF]);
+    }
+
+    #[test]
+    fn compound_assignment_tokens() {
+        assert_eq!(types("+= -= *= /= // c"), vec![
+            TokenType::PlusEqual, TokenType::MinusEqual, TokenType::StarEqual,
+            TokenType::SlashEqual, TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn star_star_is_one_token() {
+        assert_eq!(types("2 ** 3 * 4 *= 5"), vec![
+            TokenType::Number(2.0), TokenType::StarStar, TokenType::Number(3.0), TokenType::Star,
+            TokenType::Number(4.0), TokenType::StarEqual, TokenType::Number(5.0), TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn unknown_characters_match_starts_token() {
+        for c in (0..128u8).map(char::from).chain("é🦀\u{a0}".chars()) {
+            let unknown = match tokenize(&c.to_string()) {
+                Ok(_) => false,
+                Err(errors) => errors[0].message == "Unknown character",
+            };
+            assert_eq!(unknown, !Scanner::starts_token(c), "{:?}", c);
+        }
     }
 }
@@ -0,0 +1,193 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Runs the rlox binary with `args`, feeding it `stdin`.
+fn rlox(args: &[&str], stdin: &[u8]) -> Output {
+    spawn(Command::new(env!("CARGO_BIN_EXE_rlox")).args(args).env("NO_COLOR", "1"), stdin)
+}
+
+fn spawn(command: &mut Command, stdin: &[u8]) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start rlox");
+    child.stdin.take().unwrap().write_all(stdin).expect("Failed to write stdin");
+    child.wait_with_output().expect("Failed to wait for rlox")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn repl_skips_blank_lines() {
+    let output = rlox(&["repl"], b"\n   \n1 + 2\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).contains("(+ 1 2)"));
+}
+
+#[test]
+fn repl_continues_an_open_string() {
+    let output = rlox(&["repl"], b"\"abc\ndef\"\n");
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).contains("... "));
+    assert!(stdout(&output).contains("abc\ndef"));
+
+    let output = rlox(&["repl"], b"\"\"\"raw\nstring\"\"\"\n");
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).contains("raw\nstring"));
+}
+
+#[test]
+fn repl_continues_an_incomplete_expression() {
+    let output = rlox(&["repl"], b"1 +\n2\n");
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).contains("(+ 1 2)"));
+}
+
+#[test]
+fn eval_runs_an_inline_program() {
+    let output = rlox(&["ast", "-e", "1 + 2 * 3"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(+ 1 (* 2 3))\n");
+    assert_eq!(stderr(&output), "");
+
+    let output = rlox(&["--eval", "1 +"], b"");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[line 1] Error at end"));
+}
+
+#[test]
+fn dash_reads_the_program_from_stdin() {
+    let output = rlox(&["ast", "-"], b"(1 + 2) * 3\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(* (group (+ 1 2)) 3)\n");
+
+    let output = rlox(&["-"], b"1 +\n");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[line 1] Error at end\n"));
+}
+
+#[test]
+fn invalid_utf8_fails_cleanly() {
+    let output = rlox(&["-"], b"1 + \xff\xfe 2\n");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stderr(&output), "rlox: '-': input is not valid UTF-8\n");
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn help_and_version_follow_flags() {
+    let output = rlox(&["--no-color", "--help"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("usage: rlox "));
+
+    let output = rlox(&["--verbose", "--version"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), format!("rlox {}\n", env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn usage_errors_go_to_stderr() {
+    for args in [&["-e"][..], &["--eval"], &["-e", "1", "2"], &["repl", "x"], &["run", "repl", "x"], &["a", "b"]] {
+        let output = rlox(args, b"");
+        assert_eq!(output.status.code(), Some(64), "{:?}", args);
+        assert!(stderr(&output).starts_with("usage: rlox "), "{:?}", args);
+        assert_eq!(stdout(&output), "", "{:?}", args);
+    }
+}
+
+#[test]
+fn shebang_is_skipped_after_a_bom() {
+    for input in [&b"#!/usr/bin/env rlox\n1 +\n"[..], b"\xef\xbb\xbf#!/usr/bin/env rlox\n1 +\n"] {
+        let output = rlox(&["-"], input);
+        assert_eq!(output.status.code(), Some(65));
+        assert!(stderr(&output).starts_with("[line 2] Error at end\n   2 | 1 +\n"), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn tokens_subcommand_only_scans() {
+    let path = std::env::temp_dir().join(format!("rlox-tokens-{}.lox", std::process::id()));
+    std::fs::write(&path, "1 +").unwrap();
+    let output = rlox(&["tokens", path.to_str().unwrap()], b"");
+    std::fs::remove_file(&path).unwrap();
+
+    // "1 +" doesn't parse, so only the scanner ran.
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "");
+    let lines: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains("Plus"));
+    assert!(lines[2].contains("EOF"));
+}
+
+#[test]
+fn time_reports_every_phase() {
+    let output = rlox(&["--time", "-e", "1 + 2"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    let lines: Vec<String> = stderr(&output).lines().map(String::from).collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].starts_with("rlox: scan took "));
+    assert!(lines[1].starts_with("rlox: parse took "));
+
+    let output = rlox(&["--time", "tokens", "-e", "1 + 2"], b"");
+    assert!(stderr(&output).starts_with("rlox: scan took "));
+}
+
+#[test]
+fn missing_file_is_an_io_error() {
+    let output = rlox(&["/no/such/file.lox"], b"");
+    assert_eq!(output.status.code(), Some(66));
+    assert!(stderr(&output).starts_with("rlox: cannot open '/no/such/file.lox': "), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn no_color_flag_needs_no_environment() {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rlox"));
+    command.env_remove("NO_COLOR").args(["--no-color", "-e", "1 +"]);
+    let output = spawn(&mut command, b"");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[line 1] Error at end\n"));
+    assert!(!stderr(&output).contains('\x1b'), "{:?}", stderr(&output));
+}
+
+#[test]
+fn repl_after_a_mode_is_a_file_name() {
+    let dir = std::env::temp_dir().join(format!("rlox-repl-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("repl"), "1 + 2").unwrap();
+    let run = |args: &[&str]| spawn(Command::new(env!("CARGO_BIN_EXE_rlox")).args(args).current_dir(&dir), b"");
+    let ran = run(&["run", "repl"]);
+    let printed = run(&["ast", "repl"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(ran.status.code(), Some(0));
+    assert_eq!(stdout(&ran), "");
+    assert_eq!(printed.status.code(), Some(0));
+    assert_eq!(stdout(&printed), "(+ 1 2)\n");
+}
+
+#[test]
+fn flags_may_follow_a_subcommand() {
+    let output = rlox(&["tokens", "--verbose", "-e", "1"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stderr(&output), "rlox: 2 tokens, 0 diagnostics\n");
+
+    let output = rlox(&["repl", "--time"], b"1 + 2\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).starts_with("rlox: scan took "), "{}", stderr(&output));
+    assert!(stdout(&output).contains("(+ 1 2)"));
+
+    let output = rlox(&["run", "--help"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("usage: rlox "));
+}